@@ -10,15 +10,25 @@
 use std::{
     borrow::Cow,
     ops::{Deref, DerefMut},
-    sync::{atomic::AtomicUsize, Arc, Mutex},
     thread::{self, current, JoinHandle, ThreadId},
     time::Duration,
 };
 
+// `state_cnt`, below, is shared with `BasicMailbox` via `mailbox.rs`'s own
+// `cfg(loom)` swap, so every other `Arc`/`Mutex`/`AtomicUsize` in this file
+// has to come from the same place: mixing the two under `RUSTFLAGS="--cfg
+// loom"` is a std-vs-loom type mismatch (E0308) for the whole lib target,
+// since `cargo test --lib mailbox::loom_tests` still compiles all of it.
+#[cfg(not(loom))]
+use std::sync::{atomic::AtomicUsize, Arc, Mutex};
+
+#[cfg(loom)]
+use loom::sync::{atomic::AtomicUsize, Arc, Mutex};
+
 use fail::fail_point;
 use file_system::{set_io_type, IoType};
 use resource_control::{
-    channel::{unbounded, Receiver, Sender},
+    channel::{bounded, unbounded, Receiver, RecvTimeoutError, Sender},
     ResourceController,
 };
 use tikv_util::{
@@ -32,7 +42,7 @@ use crate::{
     mailbox::BasicMailbox,
     metrics::*,
     router::Router,
-    scheduler::{ControlScheduler, NormalScheduler},
+    scheduler::{ControlScheduler, NormalScheduler, SchedulerTier},
 };
 
 /// A unify type for FSMs so that they can be sent to channel easily.
@@ -198,7 +208,7 @@ impl<N: Fsm, C: Fsm> Batch<N, C> {
 
     /// Schedules the normal FSM located at `index`.
     pub fn schedule(&mut self, router: &BatchRouter<N, C>, index: usize) {
-        let to_schedule = match self.normals[index].take() {
+        let mut to_schedule = match self.normals[index].take() {
             Some(f) => f,
             None => {
                 return;
@@ -208,9 +218,22 @@ impl<N: Fsm, C: Fsm> Batch<N, C> {
             Some(ReschedulePolicy::Release(l)) => self.release(to_schedule, l),
             Some(ReschedulePolicy::Remove) => self.remove(to_schedule),
             Some(ReschedulePolicy::Schedule) => {
-                FSM_RESCHEDULE_COUNTER.get(N::FSM_TYPE).inc();
-                router.normal_scheduler.schedule(to_schedule.fsm);
-                None
+                // Use the non-blocking path here: a hot FSM is being
+                // rescheduled onto the very tier this poller thread
+                // drains, so a blocking `send` risks deadlocking the tier
+                // if every poller of it is mid-reschedule against a full
+                // queue at once. On a full queue, leave the FSM in place
+                // (below) and retry next round instead.
+                match router.normal_scheduler.try_schedule(to_schedule.fsm) {
+                    Ok(()) => {
+                        FSM_RESCHEDULE_COUNTER.get(N::FSM_TYPE).inc();
+                        None
+                    }
+                    Err(fsm) => {
+                        to_schedule.fsm = fsm;
+                        Some(to_schedule)
+                    }
+                }
             }
             None => Some(to_schedule),
         };
@@ -345,7 +368,7 @@ pub trait PollHandler<N, C>: Send + 'static {
 
     /// This function returns the priority of this handler.
     fn get_priority(&self) -> Priority {
-        Priority::Normal
+        Priority::NORMAL
     }
 }
 
@@ -353,8 +376,24 @@ pub trait PollHandler<N, C>: Send + 'static {
 pub struct Poller<N: Fsm, C: Fsm, Handler> {
     pub router: Router<N, C, NormalScheduler<N, C>, ControlScheduler<N, C>>,
     pub fsm_receiver: Receiver<FsmTypes<N, C>>,
+    /// The control FSM has its own unbounded queue so that bounding the
+    /// normal queue (see `Config::scheduler_high_watermark`) can never
+    /// deadlock delivery of control messages. Only normal-priority pollers
+    /// get one, since the control FSM is never scheduled onto the
+    /// low-priority pool.
+    pub control_receiver: Option<Receiver<FsmTypes<N, C>>>,
     pub handler: Handler,
+    /// Ceiling of the adaptive batch-size target; see `Config::max_batch_size`.
     pub max_batch_size: usize,
+    /// Floor of the adaptive batch-size target; see `Config::min_batch_size`.
+    pub min_batch_size: usize,
+    /// Smoothing factor for `backlog_ewma`; see `Config::batch_size_smoothing`.
+    pub batch_size_smoothing: f64,
+    /// EWMA of the per-round FSM backlog, used to adapt the batch-size
+    /// target between `min_batch_size` and `max_batch_size`. Starts at
+    /// `min_batch_size` so a freshly spawned poller favors latency until it
+    /// has observed some real backlog.
+    backlog_ewma: f64,
     pub reschedule_duration: Duration,
     pub joinable_workers: Option<Arc<Mutex<Vec<ThreadId>>>>,
 }
@@ -377,20 +416,55 @@ enum ReschedulePolicy {
     Schedule,
 }
 
+/// One EWMA step for the adaptive batch-size target used by `Poller::poll`:
+/// folds `backlog` into `prev_ewma` with weight `smoothing`, then clamps the
+/// result to `[min_batch_size, max_batch_size]`. Pulled out as a pure
+/// function so the smoothing/clamping math can be unit-tested without
+/// spinning up a full poller.
+fn adaptive_batch_target(
+    prev_ewma: f64,
+    backlog: usize,
+    smoothing: f64,
+    min_batch_size: usize,
+    max_batch_size: usize,
+) -> (f64, usize) {
+    let backlog_ewma = smoothing * backlog as f64 + (1.0 - smoothing) * prev_ewma;
+    let target = (backlog_ewma.round() as usize).clamp(min_batch_size, max_batch_size);
+    (backlog_ewma, target)
+}
+
 impl<N: Fsm, C: Fsm, Handler: PollHandler<N, C>> Poller<N, C, Handler> {
     fn fetch_fsm(&mut self, batch: &mut Batch<N, C>) -> bool {
         if batch.control.is_some() {
             return true;
         }
 
+        if let Some(ctrl_rx) = &self.control_receiver {
+            if let Ok(fsm) = ctrl_rx.try_recv() {
+                return batch.push(fsm);
+            }
+        }
+
         if let Ok(fsm) = self.fsm_receiver.try_recv() {
             return batch.push(fsm);
         }
 
         if batch.is_empty() {
             self.handler.pause();
-            if let Ok(fsm) = self.fsm_receiver.recv() {
-                return batch.push(fsm);
+            // Poll the control queue and the (possibly bounded) normal queue
+            // in a short timeout loop instead of a single blocking `recv`, so
+            // a control FSM isn't stuck behind an idle normal queue.
+            loop {
+                if let Some(ctrl_rx) = &self.control_receiver {
+                    if let Ok(fsm) = ctrl_rx.try_recv() {
+                        return batch.push(fsm);
+                    }
+                }
+                match self.fsm_receiver.recv_timeout(Duration::from_millis(100)) {
+                    Ok(fsm) => return batch.push(fsm),
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
             }
         }
         !batch.is_empty()
@@ -413,12 +487,31 @@ impl<N: Fsm, C: Fsm, Handler: PollHandler<N, C>> Poller<N, C, Handler> {
             // If there is some region wait to be deal, we must deal with it even if it has
             // overhead max size of batch. It's helpful to protect regions from becoming
             // hungry if some regions are hot points.
-            let mut max_batch_size = std::cmp::max(self.max_batch_size, batch.normals.len());
+            // Adapt the batch-size target to an EWMA of recent backlog (FSMs
+            // still waiting in the scheduler queues), so a quiet poller keeps
+            // batches small for latency and a busy one grows them toward
+            // `max_batch_size` for throughput, instead of living with one
+            // fixed size that's wrong for both regimes.
+            let backlog = self.fsm_receiver.len()
+                + self.control_receiver.as_ref().map_or(0, |r| r.len());
+            let (backlog_ewma, adaptive_target) = adaptive_batch_target(
+                self.backlog_ewma,
+                backlog,
+                self.batch_size_smoothing,
+                self.min_batch_size,
+                self.max_batch_size,
+            );
+            self.backlog_ewma = backlog_ewma;
+            ADAPTIVE_BATCH_SIZE_GAUGE_VEC
+                .get(N::FSM_TYPE)
+                .set(adaptive_target as i64);
+            let mut max_batch_size = std::cmp::max(adaptive_target, batch.normals.len());
             // Update some online config if needed.
             self.handler.begin(max_batch_size, |cfg| {
                 self.max_batch_size = cfg.max_batch_size();
             });
-            max_batch_size = std::cmp::max(self.max_batch_size, batch.normals.len());
+            max_batch_size =
+                std::cmp::max(adaptive_target.min(self.max_batch_size), batch.normals.len());
 
             if batch.control.is_some() {
                 let len = self.handler.handle_control(batch.control.as_mut().unwrap());
@@ -550,8 +643,11 @@ pub struct BatchSystem<N: Fsm, C: Fsm> {
     router: BatchRouter<N, C>,
     receiver: Receiver<FsmTypes<N, C>>,
     low_receiver: Receiver<FsmTypes<N, C>>,
+    control_receiver: Receiver<FsmTypes<N, C>>,
     pool_size: usize,
     max_batch_size: usize,
+    min_batch_size: usize,
+    batch_size_smoothing: f64,
     workers: Arc<Mutex<Vec<JoinHandle<()>>>>,
     joinable_workers: Arc<Mutex<Vec<ThreadId>>>,
     reschedule_duration: Duration,
@@ -575,7 +671,6 @@ where
         let pool_state_builder = self.pool_state_builder.take().unwrap();
         pool_state_builder.build(
             self.name_prefix.as_ref().unwrap().clone(),
-            self.low_priority_pool_size,
             self.workers.clone(),
             self.joinable_workers.clone(),
             handler_builder,
@@ -589,21 +684,27 @@ where
         B::Handler: Send + 'static,
     {
         let handler = builder.build(priority);
-        let receiver = match priority {
-            Priority::Normal => self.receiver.clone(),
-            Priority::Low => self.low_receiver.clone(),
+        let is_normal = priority == Priority::NORMAL;
+        let receiver = if is_normal {
+            self.receiver.clone()
+        } else {
+            self.low_receiver.clone()
         };
         let mut poller = Poller {
             router: self.router.clone(),
             fsm_receiver: receiver,
-            handler,
-            max_batch_size: self.max_batch_size,
-            reschedule_duration: self.reschedule_duration,
-            joinable_workers: if priority == Priority::Normal {
-                Some(Arc::clone(&self.joinable_workers))
+            control_receiver: if is_normal {
+                Some(self.control_receiver.clone())
             } else {
                 None
             },
+            handler,
+            max_batch_size: self.max_batch_size,
+            min_batch_size: self.min_batch_size,
+            batch_size_smoothing: self.batch_size_smoothing,
+            backlog_ewma: self.min_batch_size as f64,
+            reschedule_duration: self.reschedule_duration,
+            joinable_workers: Some(Arc::clone(&self.joinable_workers)),
         };
         let props = tikv_util::thread_group::current_properties();
         let t = thread::Builder::new()
@@ -626,20 +727,126 @@ where
         for i in 0..self.pool_size {
             self.start_poller(
                 thd_name!(format!("{}-{}", name_prefix, i)),
-                Priority::Normal,
+                Priority::NORMAL,
                 &mut builder,
             );
         }
         for i in 0..self.low_priority_pool_size {
             self.start_poller(
                 thd_name!(format!("{}-low-{}", name_prefix, i)),
-                Priority::Low,
+                Priority::LOW,
                 &mut builder,
             );
         }
         self.name_prefix = Some(name_prefix);
     }
 
+    /// Resizes the normal-priority poller pool to contain exactly `size`
+    /// pollers, spawning additional pollers or retiring idle ones at
+    /// runtime, without restarting the node.
+    ///
+    /// Shrinking sends one `Empty` signal per retired poller onto the
+    /// normal queue; whichever poller happens to pick it up finishes
+    /// draining its current batch and exits, landing its `ThreadId` in
+    /// `joinable_workers` for `shutdown` (or the next `resize_pool` call)
+    /// to join.
+    pub fn resize_pool<B>(&mut self, size: usize, builder: &mut B)
+    where
+        B: HandlerBuilder<N, C>,
+        B::Handler: Send + 'static,
+    {
+        self.resize_pool_imp(Priority::NORMAL, size, builder);
+    }
+
+    /// Same as [`resize_pool`], but for the low-priority poller pool.
+    pub fn resize_low_priority_pool<B>(&mut self, size: usize, builder: &mut B)
+    where
+        B: HandlerBuilder<N, C>,
+        B::Handler: Send + 'static,
+    {
+        self.resize_pool_imp(Priority::LOW, size, builder);
+    }
+
+    fn resize_pool_imp<B>(&mut self, priority: Priority, size: usize, builder: &mut B)
+    where
+        B: HandlerBuilder<N, C>,
+        B::Handler: Send + 'static,
+    {
+        self.reap_joinable_workers();
+        let is_normal = priority == Priority::NORMAL;
+        if self.name_prefix.is_none() {
+            // The system hasn't been spawned yet, just remember the size.
+            if is_normal {
+                self.pool_size = size;
+            } else {
+                self.low_priority_pool_size = size;
+            }
+            return;
+        }
+        let name_prefix = self.name_prefix.clone().unwrap();
+        let current = if is_normal {
+            self.pool_size
+        } else {
+            self.low_priority_pool_size
+        };
+        match size.cmp(&current) {
+            std::cmp::Ordering::Equal => return,
+            std::cmp::Ordering::Greater => {
+                for i in current..size {
+                    let name = if is_normal {
+                        thd_name!(format!("{}-{}", name_prefix, i))
+                    } else {
+                        thd_name!(format!("{}-low-{}", name_prefix, i))
+                    };
+                    self.start_poller(name, priority, builder);
+                }
+            }
+            std::cmp::Ordering::Less => {
+                let sender = self.router.normal_scheduler.sender(priority);
+                for _ in size..current {
+                    let _ = sender.send(FsmTypes::Empty);
+                }
+            }
+        }
+        if is_normal {
+            self.pool_size = size;
+        } else {
+            self.low_priority_pool_size = size;
+        }
+        info!(
+            "resized {:?} priority pool of batch system {} to {}",
+            priority, name_prefix, size
+        );
+    }
+
+    /// Joins and drops the `JoinHandle`s of pollers that have already
+    /// retired, as recorded in `joinable_workers` by [`Poller::drop`].
+    /// Keeps `workers` from accumulating handles of threads that already
+    /// exited, e.g. after a `resize_pool` shrink.
+    fn reap_joinable_workers(&mut self) {
+        let ids: Vec<ThreadId> = self.joinable_workers.lock().unwrap().drain(..).collect();
+        if ids.is_empty() {
+            return;
+        }
+        let mut retired = Vec::with_capacity(ids.len());
+        {
+            let mut workers = self.workers.lock().unwrap();
+            let mut i = 0;
+            while i < workers.len() {
+                if ids.contains(&workers[i].thread().id()) {
+                    retired.push(workers.swap_remove(i));
+                } else {
+                    i += 1;
+                }
+            }
+        }
+        for h in retired {
+            if let Err(e) = h.join() {
+                error!("failed to join retired worker thread: {:?}", e);
+            }
+        }
+    }
+
     /// Shutdown the batch system and wait till all background threads exit.
     pub fn shutdown(&mut self) {
         if self.name_prefix.is_none() {
@@ -648,6 +855,22 @@ where
         let name_prefix = self.name_prefix.take().unwrap();
         info!("shutdown batch system {}", name_prefix);
         self.router.broadcast_shutdown();
+        // One `Empty` per poller: each normal/low-priority poller consumes
+        // exactly one before retiring, same as a `resize_pool` shrink.
+        for _ in 0..self.pool_size {
+            let _ = self
+                .router
+                .normal_scheduler
+                .sender(Priority::NORMAL)
+                .send(FsmTypes::Empty);
+        }
+        for _ in 0..self.low_priority_pool_size {
+            let _ = self
+                .router
+                .normal_scheduler
+                .sender(Priority::LOW)
+                .send(FsmTypes::Empty);
+        }
         let mut last_error = None;
         for h in self.workers.lock().unwrap().drain(..) {
             debug!("waiting for {}", h.thread().name().unwrap());
@@ -665,6 +888,8 @@ where
 
 struct PoolStateBuilder<N: Fsm, C: Fsm> {
     max_batch_size: usize,
+    min_batch_size: usize,
+    batch_size_smoothing: f64,
     reschedule_duration: Duration,
     fsm_receiver: Receiver<FsmTypes<N, C>>,
     fsm_sender: Sender<FsmTypes<N, C>>,
@@ -675,7 +900,6 @@ impl<N: Fsm, C: Fsm> PoolStateBuilder<N, C> {
     fn build<H: HandlerBuilder<N, C>>(
         self,
         name_prefix: String,
-        low_priority_pool_size: usize,
         workers: Arc<Mutex<Vec<JoinHandle<()>>>>,
         joinable_workers: Arc<Mutex<Vec<ThreadId>>>,
         handler_builder: H,
@@ -686,11 +910,12 @@ impl<N: Fsm, C: Fsm> PoolStateBuilder<N, C> {
             handler_builder,
             fsm_receiver: self.fsm_receiver,
             fsm_sender: self.fsm_sender,
-            low_priority_pool_size,
             workers,
             joinable_workers,
             expected_pool_size: self.pool_size,
             max_batch_size: self.max_batch_size,
+            min_batch_size: self.min_batch_size,
+            batch_size_smoothing: self.batch_size_smoothing,
             reschedule_duration: self.reschedule_duration,
             id_base,
         }
@@ -702,11 +927,12 @@ pub struct PoolState<N: Fsm, C: Fsm, H: HandlerBuilder<N, C>> {
     pub handler_builder: H,
     pub fsm_receiver: Receiver<FsmTypes<N, C>>,
     pub fsm_sender: Sender<FsmTypes<N, C>>,
-    pub low_priority_pool_size: usize,
     pub expected_pool_size: usize,
     pub workers: Arc<Mutex<Vec<JoinHandle<()>>>>,
     pub joinable_workers: Arc<Mutex<Vec<ThreadId>>>,
     pub max_batch_size: usize,
+    pub min_batch_size: usize,
+    pub batch_size_smoothing: f64,
     pub reschedule_duration: Duration,
     pub id_base: usize,
 }
@@ -723,19 +949,47 @@ pub fn create_system<N: Fsm, C: Fsm>(
     controller: Box<C>,
     resource_ctl: Option<Arc<ResourceController>>,
 ) -> (BatchRouter<N, C>, BatchSystem<N, C>) {
+    if let Err(e) = cfg.validate() {
+        safe_panic!("invalid batch-system config: {}", e);
+    }
     let state_cnt = Arc::new(AtomicUsize::new(0));
     let control_box = BasicMailbox::new(sender, controller, state_cnt.clone());
-    let (sender, receiver) = unbounded(resource_ctl);
-    let (low_sender, low_receiver) = unbounded(None); // no resource control for low fsm
+    // An optional high-watermark caps the number of ready FSMs queued on the
+    // normal scheduler so a flood of messages to slow regions can't grow the
+    // queue without bound and OOM the process; `schedule` then blocks to push
+    // the backpressure upstream. The control queue always stays unbounded:
+    // bounding it could deadlock, since the control FSM itself drives work
+    // that drains the normal queue.
+    let low_resource_ctl = if cfg.low_priority_resource_control {
+        resource_ctl.clone()
+    } else {
+        None
+    };
+    let (sender, receiver) = match cfg.scheduler_high_watermark {
+        Some(cap) => bounded(cap, resource_ctl.clone()),
+        None => unbounded(resource_ctl.clone()),
+    };
+    let (low_sender, low_receiver) = unbounded(low_resource_ctl.clone());
+    let (control_sender, control_receiver) = unbounded(None);
     let normal_scheduler = NormalScheduler {
-        sender: sender.clone(),
-        low_sender,
+        tiers: vec![
+            SchedulerTier {
+                sender: sender.clone(),
+                resource_ctl,
+            },
+            SchedulerTier {
+                sender: low_sender,
+                resource_ctl: low_resource_ctl,
+            },
+        ],
     };
     let control_scheduler = ControlScheduler {
-        sender: sender.clone(),
+        sender: control_sender,
     };
     let pool_state_builder = PoolStateBuilder {
         max_batch_size: cfg.max_batch_size(),
+        min_batch_size: cfg.min_batch_size,
+        batch_size_smoothing: cfg.batch_size_smoothing,
         reschedule_duration: cfg.reschedule_duration.0,
         fsm_receiver: receiver.clone(),
         fsm_sender: sender,
@@ -747,8 +1001,11 @@ pub fn create_system<N: Fsm, C: Fsm>(
         router: router.clone(),
         receiver,
         low_receiver,
+        control_receiver,
         pool_size: cfg.pool_size,
         max_batch_size: cfg.max_batch_size(),
+        min_batch_size: cfg.min_batch_size,
+        batch_size_smoothing: cfg.batch_size_smoothing,
         workers: Arc::new(Mutex::new(Vec::new())),
         joinable_workers: Arc::new(Mutex::new(Vec::new())),
         reschedule_duration: cfg.reschedule_duration.0,
@@ -757,3 +1014,141 @@ pub fn create_system<N: Fsm, C: Fsm>(
     };
     (router, system)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant as StdInstant;
+
+    use super::*;
+    use crate::config::Config;
+
+    struct NoopFsm;
+
+    impl Fsm for NoopFsm {
+        type Message = ();
+        const FSM_TYPE: &'static str = "noop";
+
+        fn is_stopped(&self) -> bool {
+            false
+        }
+    }
+
+    struct NoopHandler;
+
+    impl PollHandler<NoopFsm, NoopFsm> for NoopHandler {
+        fn begin<F>(&mut self, _batch_size: usize, _update_cfg: F)
+        where
+            for<'a> F: FnOnce(&'a Config),
+        {
+        }
+
+        fn handle_control(&mut self, _control: &mut NoopFsm) -> Option<usize> {
+            Some(0)
+        }
+
+        fn handle_normal(
+            &mut self,
+            _normal: &mut impl DerefMut<Target = NoopFsm>,
+        ) -> HandleResult {
+            HandleResult::StopAt {
+                progress: 0,
+                skip_end: false,
+            }
+        }
+
+        fn end(&mut self, _batch: &mut [Option<impl DerefMut<Target = NoopFsm>>]) {}
+    }
+
+    struct NoopHandlerBuilder;
+
+    impl HandlerBuilder<NoopFsm, NoopFsm> for NoopHandlerBuilder {
+        type Handler = NoopHandler;
+
+        fn build(&mut self, _priority: Priority) -> NoopHandler {
+            NoopHandler
+        }
+    }
+
+    fn new_test_system(cfg: &Config) -> (BatchRouter<NoopFsm, NoopFsm>, BatchSystem<NoopFsm, NoopFsm>) {
+        let (tx, _rx) = mpsc::loose_bounded(8);
+        create_system(cfg, tx, Box::new(NoopFsm), None)
+    }
+
+    /// Waits up to ~1s for `cond` to become true, polling every 10ms, so
+    /// assertions on background poller threads exiting don't race a fixed
+    /// sleep.
+    fn wait_until(mut cond: impl FnMut() -> bool) -> bool {
+        let start = StdInstant::now();
+        while start.elapsed() < Duration::from_secs(1) {
+            if cond() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        cond()
+    }
+
+    #[test]
+    fn resize_pool_grows_the_worker_count() {
+        let cfg = Config {
+            pool_size: 2,
+            low_priority_pool_size: 1,
+            ..Default::default()
+        };
+        let (_router, mut system) = new_test_system(&cfg);
+        system.spawn("resize-grow".to_owned(), NoopHandlerBuilder);
+        assert_eq!(system.workers.lock().unwrap().len(), 3);
+
+        system.resize_pool(4, &mut NoopHandlerBuilder);
+        assert_eq!(system.pool_size, 4);
+        assert_eq!(system.workers.lock().unwrap().len(), 5);
+
+        system.shutdown();
+    }
+
+    #[test]
+    fn resize_pool_shrink_then_grow_reaps_joinable_workers() {
+        let cfg = Config {
+            pool_size: 2,
+            low_priority_pool_size: 1,
+            ..Default::default()
+        };
+        let (_router, mut system) = new_test_system(&cfg);
+        system.spawn("resize-shrink".to_owned(), NoopHandlerBuilder);
+
+        system.resize_pool(0, &mut NoopHandlerBuilder);
+        assert_eq!(system.pool_size, 0);
+        assert!(
+            wait_until(|| system.joinable_workers.lock().unwrap().len() == 2),
+            "both normal pollers should have retired and recorded their ThreadId"
+        );
+
+        // Growing back reaps the retired handles before spawning new ones,
+        // so `workers` never accumulates handles of threads that already
+        // exited.
+        system.resize_pool(1, &mut NoopHandlerBuilder);
+        assert_eq!(system.joinable_workers.lock().unwrap().len(), 0);
+        assert_eq!(system.workers.lock().unwrap().len(), 2);
+
+        system.shutdown();
+    }
+
+    #[test]
+    fn adaptive_batch_target_tracks_backlog_within_bounds() {
+        // A quiet poller (no backlog) decays its EWMA toward 0, one
+        // smoothing step at a time.
+        let (ewma, target) = adaptive_batch_target(10.0, 0, 0.5, 2, 256);
+        assert_eq!(ewma, 5.0);
+        assert_eq!(target, 5);
+
+        // A floor still applies even once the raw EWMA would round below
+        // `min_batch_size`.
+        let (ewma, target) = adaptive_batch_target(1.0, 0, 0.5, 2, 256);
+        assert_eq!(ewma, 0.5);
+        assert_eq!(target, 2);
+
+        // A sustained large backlog saturates at `max_batch_size`.
+        let (_, target) = adaptive_batch_target(256.0, 10_000, 0.5, 1, 256);
+        assert_eq!(target, 256);
+    }
+}
@@ -0,0 +1,118 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+use online_config::OnlineConfig;
+use serde::{Deserialize, Serialize};
+use tikv_util::config::ReadableDuration;
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, OnlineConfig)]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct Config {
+    /// Ceiling of the adaptive batch-size target (see `min_batch_size` and
+    /// `batch_size_smoothing`); the target never grows past this many FSMs
+    /// per round regardless of backlog, though a single already-fetched
+    /// batch can still exceed it to avoid starving hot FSMs (see
+    /// `Poller::poll`).
+    pub max_batch_size: Option<usize>,
+    pub pool_size: usize,
+    pub low_priority_pool_size: usize,
+    pub reschedule_duration: ReadableDuration,
+    /// Floor of the adaptive batch-size target. The target never shrinks
+    /// below this many FSMs per round even when the scheduler queues have
+    /// been empty for a while. The default of 1 lets a nearly idle poller
+    /// shrink its batches all the way down to a single FSM.
+    pub min_batch_size: usize,
+    /// Smoothing factor in `(0.0, 1.0]` for the EWMA of per-round FSM
+    /// backlog (FSMs waiting in the scheduler queues) that the batch-size
+    /// target adapts to. Larger values track backlog swings faster; smaller
+    /// values are steadier but slower to grow the batch size under a
+    /// sudden burst.
+    pub batch_size_smoothing: f64,
+    /// High-watermark on the number of ready FSMs queued on the normal
+    /// scheduler. When set, `NormalScheduler::schedule` blocks once the
+    /// queue reaches this depth, applying backpressure to whatever is
+    /// scheduling FSMs instead of letting the queue grow without bound.
+    /// The control queue is always unbounded to avoid deadlock. `None`
+    /// keeps the previous unbounded behavior.
+    pub scheduler_high_watermark: Option<usize>,
+    /// Whether the low-priority scheduler tier shares the store's
+    /// `ResourceController` instead of running unthrottled. Lets
+    /// background GC/compaction-triggered FSMs be rate-limited like
+    /// everything else instead of always running at full speed.
+    pub low_priority_resource_control: bool,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            max_batch_size: None,
+            pool_size: 2,
+            low_priority_pool_size: 1,
+            reschedule_duration: ReadableDuration::secs(5),
+            min_batch_size: 1,
+            batch_size_smoothing: 0.3,
+            scheduler_high_watermark: None,
+            low_priority_resource_control: false,
+        }
+    }
+}
+
+impl Config {
+    pub fn max_batch_size(&self) -> usize {
+        self.max_batch_size.unwrap_or(256)
+    }
+
+    /// Validates the adaptive batch-size knobs. `Poller::poll` feeds
+    /// `min_batch_size`/`max_batch_size` straight into a `clamp`, which
+    /// panics if `min_batch_size > max_batch_size`; `batch_size_smoothing`
+    /// outside `(0.0, 1.0]` makes the EWMA either never move or overshoot
+    /// on every tick.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.min_batch_size > self.max_batch_size() {
+            return Err(format!(
+                "min-batch-size {} is larger than max-batch-size {}",
+                self.min_batch_size,
+                self.max_batch_size()
+            ));
+        }
+        if !(self.batch_size_smoothing > 0.0 && self.batch_size_smoothing <= 1.0) {
+            return Err(format!(
+                "batch-size-smoothing {} must be in (0.0, 1.0]",
+                self.batch_size_smoothing
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_rejects_inverted_batch_size_bounds() {
+        let cfg = Config {
+            min_batch_size: 8,
+            max_batch_size: Some(4),
+            ..Default::default()
+        };
+        assert!(cfg.validate().is_err());
+    }
+
+    #[test]
+    fn validate_rejects_smoothing_out_of_range() {
+        let mut cfg = Config {
+            batch_size_smoothing: 0.0,
+            ..Default::default()
+        };
+        assert!(cfg.validate().is_err());
+
+        cfg.batch_size_smoothing = 1.5;
+        assert!(cfg.validate().is_err());
+    }
+
+    #[test]
+    fn validate_accepts_the_default_config() {
+        assert!(Config::default().validate().is_ok());
+    }
+}
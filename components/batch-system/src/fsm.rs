@@ -0,0 +1,70 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::borrow::Cow;
+
+use crate::mailbox::BasicMailbox;
+
+/// Priority of an FSM, used by [`crate::scheduler::NormalScheduler`] to
+/// route it to one of its priority tiers.
+///
+/// Represented as an index into the scheduler's tier vector, rather than a
+/// fixed set of variants, so the number of tiers is a runtime/config detail
+/// instead of being baked into the type. `NORMAL` and `LOW` are the only
+/// tiers any `Fsm` declares today.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Priority(usize);
+
+impl Priority {
+    pub const NORMAL: Priority = Priority(0);
+    pub const LOW: Priority = Priority(1);
+
+    /// Index into the scheduler's per-tier queue vector.
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+/// A Fsm is a finite state machine. It should be able to be notified for
+/// updating internal state according to incoming messages.
+pub trait Fsm {
+    type Message: Send;
+
+    /// Used to label metrics, e.g. `"raft"` or `"apply"`.
+    const FSM_TYPE: &'static str;
+
+    fn is_stopped(&self) -> bool;
+
+    /// Set a mailbox to Fsm, which should be used to send message to itself.
+    fn set_mailbox(&mut self, _mailbox: Cow<'_, BasicMailbox<Self>>)
+    where
+        Self: Sized,
+    {
+    }
+
+    /// Take the mailbox from Fsm. Implementation should ensure there will be
+    /// no reference to mailbox after calling this method.
+    fn take_mailbox(&mut self) -> Option<BasicMailbox<Self>>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
+    /// The priority of this Fsm, used to decide which queue it's scheduled
+    /// on.
+    fn get_priority(&self) -> Priority {
+        Priority::NORMAL
+    }
+}
+
+/// A scheduler for `Fsm` that is used to schedule FSMs for later handling.
+pub trait FsmScheduler {
+    type Fsm: Fsm;
+
+    /// Schedule a Fsm for later handling.
+    fn schedule(&self, fsm: Box<Self::Fsm>);
+
+    /// Shutdown the scheduler, which indicates that resources like
+    /// background thread pool should be released.
+    fn shutdown(&self);
+}
@@ -0,0 +1,24 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! A batch system for polling user defined FSMs (finite state machines)
+//! efficiently, used by both the raftstore and the apply subsystem.
+
+mod batch;
+mod config;
+mod fsm;
+mod mailbox;
+mod metrics;
+mod router;
+mod scheduler;
+
+pub use crate::{
+    batch::{
+        create_system, Batch, BatchRouter, BatchSystem, HandleResult, HandlerBuilder, NormalFsm,
+        PollHandler, Poller, PoolState,
+    },
+    config::Config,
+    fsm::{Fsm, FsmScheduler, Priority},
+    mailbox::BasicMailbox,
+    router::Router,
+    scheduler::{ControlScheduler, NormalScheduler},
+};
@@ -0,0 +1,100 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge_vec, Histogram,
+    HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
+};
+
+/// A thin wrapper so call sites can write `METRIC.get(Fsm::FSM_TYPE)` instead
+/// of repeating `.with_label_values(&[..])` everywhere.
+pub struct AutoFlushHistogramVec(HistogramVec);
+
+impl AutoFlushHistogramVec {
+    pub fn get(&self, fsm_type: &str) -> Histogram {
+        self.0.with_label_values(&[fsm_type])
+    }
+}
+
+pub struct AutoFlushIntCounterVec(IntCounterVec);
+
+impl AutoFlushIntCounterVec {
+    pub fn get(&self, fsm_type: &str) -> IntCounter {
+        self.0.with_label_values(&[fsm_type])
+    }
+}
+
+pub struct AutoFlushIntGaugeVec(IntGaugeVec);
+
+impl AutoFlushIntGaugeVec {
+    pub fn get(&self, fsm_type: &str) -> IntGauge {
+        self.0.with_label_values(&[fsm_type])
+    }
+}
+
+lazy_static! {
+    pub static ref FSM_POLL_ROUND: AutoFlushHistogramVec = AutoFlushHistogramVec(
+        register_histogram_vec!(
+            "tikv_batch_system_fsm_poll_round",
+            "Histogram of the number of rounds an FSM was continuously polled",
+            &["type"]
+        )
+        .unwrap()
+    );
+    pub static ref FSM_POLL_DURATION: AutoFlushHistogramVec = AutoFlushHistogramVec(
+        register_histogram_vec!(
+            "tikv_batch_system_fsm_poll_duration",
+            "Histogram of the duration an FSM stayed continuously polled",
+            &["type"]
+        )
+        .unwrap()
+    );
+    pub static ref FSM_COUNT_PER_POLL: AutoFlushHistogramVec = AutoFlushHistogramVec(
+        register_histogram_vec!(
+            "tikv_batch_system_fsm_count_per_poll",
+            "Histogram of the number of FSMs polled in a single round",
+            &["type"]
+        )
+        .unwrap()
+    );
+    pub static ref FSM_SCHEDULE_WAIT_DURATION: AutoFlushHistogramVec = AutoFlushHistogramVec(
+        register_histogram_vec!(
+            "tikv_batch_system_fsm_schedule_wait_duration",
+            "Histogram of the duration an FSM waited in the scheduling queue",
+            &["type"]
+        )
+        .unwrap()
+    );
+    pub static ref FSM_RESCHEDULE_COUNTER: AutoFlushIntCounterVec = AutoFlushIntCounterVec(
+        register_int_counter_vec!(
+            "tikv_batch_system_fsm_reschedule_total",
+            "Total number of times an FSM was rescheduled to another poller",
+            &["type"]
+        )
+        .unwrap()
+    );
+    pub static ref CHANNEL_FULL_COUNTER_VEC: AutoFlushIntCounterVec = AutoFlushIntCounterVec(
+        register_int_counter_vec!(
+            "tikv_batch_system_channel_full_total",
+            "Total number of channel full errors",
+            &["type"]
+        )
+        .unwrap()
+    );
+    pub static ref NORMAL_QUEUE_SIZE_GAUGE_VEC: AutoFlushIntGaugeVec = AutoFlushIntGaugeVec(
+        register_int_gauge_vec!(
+            "tikv_batch_system_normal_queue_size",
+            "Number of ready FSMs currently queued on the normal scheduler",
+            &["priority"]
+        )
+        .unwrap()
+    );
+    pub static ref ADAPTIVE_BATCH_SIZE_GAUGE_VEC: AutoFlushIntGaugeVec = AutoFlushIntGaugeVec(
+        register_int_gauge_vec!(
+            "tikv_batch_system_adaptive_batch_size",
+            "Current adaptive batch-size target, between min_batch_size and max_batch_size",
+            &["type"]
+        )
+        .unwrap()
+    );
+}
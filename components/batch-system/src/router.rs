@@ -0,0 +1,71 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+// Must track `mailbox.rs`'s own `cfg(loom)` swap: `state_cnt` here is the
+// same `Arc<AtomicUsize>` threaded into `BasicMailbox::new`, so building the
+// whole lib target under `RUSTFLAGS="--cfg loom"` needs this file's `Arc`/
+// `AtomicUsize` to resolve to loom's, not std's.
+#[cfg(not(loom))]
+use std::sync::{atomic::AtomicUsize, Arc};
+
+#[cfg(loom)]
+use loom::sync::{atomic::AtomicUsize, Arc};
+
+use crate::{
+    fsm::{Fsm, FsmScheduler},
+    mailbox::BasicMailbox,
+};
+
+/// Router routes messages to its target mailbox.
+///
+/// Every Fsm has a mailbox, hence it's not required to have a `Fsm` type
+/// specified for a Router.
+pub struct Router<N: Fsm, C: Fsm, Ns, Cs> {
+    pub(crate) control_box: BasicMailbox<C>,
+    pub normal_scheduler: Ns,
+    pub control_scheduler: Cs,
+    pub(crate) state_cnt: Arc<AtomicUsize>,
+    _phantom: std::marker::PhantomData<N>,
+}
+
+impl<N, C, Ns, Cs> Router<N, C, Ns, Cs>
+where
+    N: Fsm,
+    C: Fsm,
+    Ns: FsmScheduler<Fsm = N> + Clone,
+    Cs: FsmScheduler<Fsm = C> + Clone,
+{
+    pub fn new(
+        control_box: BasicMailbox<C>,
+        normal_scheduler: Ns,
+        control_scheduler: Cs,
+        state_cnt: Arc<AtomicUsize>,
+    ) -> Router<N, C, Ns, Cs> {
+        Router {
+            control_box,
+            normal_scheduler,
+            control_scheduler,
+            state_cnt,
+            _phantom: std::marker::PhantomData,
+        }
+    }
+
+    /// Sends `FsmTypes::Empty` on the control queue; whichever
+    /// normal-priority poller picks it up exits. The per-poller `Empty`
+    /// signals for the normal and low-priority pools are sent separately by
+    /// `BatchSystem::shutdown`, which knows how many pollers each pool has.
+    pub fn broadcast_shutdown(&self) {
+        self.control_scheduler.shutdown();
+    }
+}
+
+impl<N: Fsm, C: Fsm, Ns: Clone, Cs: Clone> Clone for Router<N, C, Ns, Cs> {
+    fn clone(&self) -> Router<N, C, Ns, Cs> {
+        Router {
+            control_box: self.control_box.clone(),
+            normal_scheduler: self.normal_scheduler.clone(),
+            control_scheduler: self.control_scheduler.clone(),
+            state_cnt: self.state_cnt.clone(),
+            _phantom: std::marker::PhantomData,
+        }
+    }
+}
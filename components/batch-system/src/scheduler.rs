@@ -0,0 +1,257 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Routes normal FSMs onto priority tiers, each with its own queue and
+//! optional resource control. [`crate::batch::create_system`] builds exactly
+//! two tiers (`NORMAL`/`LOW`), with one dedicated poller pool per tier; no
+//! poller drains more than one tier, and there is no starvation guard
+//! between tiers. See [`NormalScheduler`]'s doc for specifics.
+
+use std::sync::Arc;
+
+use resource_control::{
+    channel::{Sender, TrySendError},
+    ResourceController,
+};
+use tikv_util::time::Instant;
+
+use crate::{
+    batch::FsmTypes,
+    fsm::{Fsm, FsmScheduler, Priority},
+    metrics::NORMAL_QUEUE_SIZE_GAUGE_VEC,
+};
+
+/// One priority tier of the normal scheduler: its own queue, and an
+/// optional resource controller so, e.g., a low-priority tier carrying
+/// background GC/compaction-triggered FSMs can be rate-limited instead of
+/// running unthrottled.
+pub(crate) struct SchedulerTier<N, C> {
+    pub(crate) sender: Sender<FsmTypes<N, C>>,
+    pub(crate) resource_ctl: Option<Arc<ResourceController>>,
+}
+
+/// A scheduler that routes normal FSMs to one of N priority tiers, keyed by
+/// [`Fsm::get_priority`]. Tier 0 is the default/normal tier; further tiers
+/// (e.g. tier 1, "low") are configured in [`crate::config::Config`].
+///
+/// The queue-routing machinery here is generic over the tier count, but the
+/// rest of the crate isn't: [`crate::batch::BatchSystem`] only ever spawns
+/// and resizes exactly two poller pools (`Priority::NORMAL`/`Priority::LOW`),
+/// one dedicated to each tier. There's no poller that drains more than one
+/// tier, so a third tier would have a queue but no poller to serve it.
+pub struct NormalScheduler<N, C> {
+    pub(crate) tiers: Vec<SchedulerTier<N, C>>,
+}
+
+impl<N, C> NormalScheduler<N, C> {
+    /// The queue backing `priority`, clamped to the lowest configured tier
+    /// if the Fsm declares a priority beyond what's configured.
+    pub(crate) fn sender(&self, priority: Priority) -> &Sender<FsmTypes<N, C>> {
+        let idx = priority.index().min(self.tiers.len() - 1);
+        &self.tiers[idx].sender
+    }
+}
+
+impl<N, C> Clone for NormalScheduler<N, C> {
+    #[inline]
+    fn clone(&self) -> Self {
+        NormalScheduler {
+            tiers: self
+                .tiers
+                .iter()
+                .map(|t| SchedulerTier {
+                    sender: t.sender.clone(),
+                    resource_ctl: t.resource_ctl.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl<N: Fsm, C> FsmScheduler for NormalScheduler<N, C> {
+    type Fsm = N;
+
+    #[inline]
+    fn schedule(&self, fsm: Box<N>) {
+        let priority = fsm.get_priority();
+        let idx = priority.index().min(self.tiers.len() - 1);
+        let sender = &self.tiers[idx].sender;
+        // If `sender` is bounded (see `Config::scheduler_high_watermark`),
+        // this blocks once the queue is full, pushing backpressure onto
+        // whoever is scheduling the FSM instead of growing the queue
+        // without bound.
+        if let Err(e) = sender.send(FsmTypes::Normal((fsm, Instant::now_coarse()))) {
+            // The only way a send can fail here is if every receiver, i.e.
+            // every poller of this tier, has already been dropped.
+            match e.into_inner() {
+                FsmTypes::Normal((mut fsm, _)) => fsm.take_mailbox().map(|mb| mb.close()),
+                _ => unreachable!(),
+            };
+            return;
+        }
+        NORMAL_QUEUE_SIZE_GAUGE_VEC
+            .get(tier_label(idx))
+            .set(sender.len() as i64);
+    }
+
+    #[inline]
+    fn shutdown(&self) {
+        for tier in &self.tiers {
+            let _ = tier.sender.send(FsmTypes::Empty);
+        }
+    }
+}
+
+impl<N: Fsm, C> NormalScheduler<N, C> {
+    /// Non-blocking counterpart to [`FsmScheduler::schedule`], for the
+    /// self-feeding reschedule path in `Batch::schedule`, where a poller
+    /// puts an FSM it just finished handling back onto the very tier its
+    /// own thread drains. A blocking `send` there can deadlock the tier:
+    /// if every poller of it is mid-reschedule at once while the queue
+    /// sits at its high-watermark, nobody is left running `recv` to free
+    /// space. Returns the FSM back on `Err` instead of blocking, so the
+    /// caller can leave it where it is and retry next round.
+    pub(crate) fn try_schedule(&self, fsm: Box<N>) -> Result<(), Box<N>> {
+        let priority = fsm.get_priority();
+        let idx = priority.index().min(self.tiers.len() - 1);
+        let sender = &self.tiers[idx].sender;
+        match sender.try_send(FsmTypes::Normal((fsm, Instant::now_coarse()))) {
+            Ok(()) => {
+                NORMAL_QUEUE_SIZE_GAUGE_VEC
+                    .get(tier_label(idx))
+                    .set(sender.len() as i64);
+                Ok(())
+            }
+            Err(TrySendError::Full(FsmTypes::Normal((fsm, _)))) => Err(fsm),
+            Err(TrySendError::Disconnected(FsmTypes::Normal((mut fsm, _)))) => {
+                // Every receiver, i.e. every poller of this tier, has
+                // already been dropped.
+                fsm.take_mailbox().map(|mb| mb.close());
+                Ok(())
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+fn tier_label(idx: usize) -> &'static str {
+    match idx {
+        0 => "normal",
+        1 => "low",
+        _ => "extra",
+    }
+}
+
+/// A scheduler that schedules the control FSM for later handling.
+pub struct ControlScheduler<N, C> {
+    pub(crate) sender: Sender<FsmTypes<N, C>>,
+}
+
+impl<N, C> Clone for ControlScheduler<N, C> {
+    #[inline]
+    fn clone(&self) -> Self {
+        ControlScheduler {
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl<N, C: Fsm> FsmScheduler for ControlScheduler<N, C> {
+    type Fsm = C;
+
+    #[inline]
+    fn schedule(&self, fsm: Box<C>) {
+        let _ = self
+            .sender
+            .send(FsmTypes::Control((fsm, Instant::now_coarse())));
+    }
+
+    #[inline]
+    fn shutdown(&self) {
+        let _ = self.sender.send(FsmTypes::Empty);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use resource_control::channel::{bounded, unbounded};
+
+    use super::*;
+
+    struct TestFsm(Priority);
+
+    impl Fsm for TestFsm {
+        type Message = ();
+        const FSM_TYPE: &'static str = "test";
+
+        fn is_stopped(&self) -> bool {
+            false
+        }
+
+        fn get_priority(&self) -> Priority {
+            self.0
+        }
+    }
+
+    fn test_scheduler(
+        normal_cap: Option<usize>,
+    ) -> (
+        NormalScheduler<TestFsm, TestFsm>,
+        resource_control::channel::Receiver<FsmTypes<TestFsm, TestFsm>>,
+        resource_control::channel::Receiver<FsmTypes<TestFsm, TestFsm>>,
+    ) {
+        let (normal_tx, normal_rx) = match normal_cap {
+            Some(cap) => bounded(cap, None),
+            None => unbounded(None),
+        };
+        let (low_tx, low_rx) = unbounded(None);
+        let scheduler = NormalScheduler {
+            tiers: vec![
+                SchedulerTier {
+                    sender: normal_tx,
+                    resource_ctl: None,
+                },
+                SchedulerTier {
+                    sender: low_tx,
+                    resource_ctl: None,
+                },
+            ],
+        };
+        (scheduler, normal_rx, low_rx)
+    }
+
+    fn priority_of(fsm: &FsmTypes<TestFsm, TestFsm>) -> Priority {
+        match fsm {
+            FsmTypes::Normal((f, _)) => f.0,
+            _ => panic!("expected a normal fsm"),
+        }
+    }
+
+    #[test]
+    fn schedule_routes_by_declared_priority() {
+        let (scheduler, normal_rx, low_rx) = test_scheduler(None);
+
+        scheduler.schedule(Box::new(TestFsm(Priority::NORMAL)));
+        scheduler.schedule(Box::new(TestFsm(Priority::LOW)));
+
+        assert_eq!(priority_of(&normal_rx.try_recv().unwrap()), Priority::NORMAL);
+        assert_eq!(priority_of(&low_rx.try_recv().unwrap()), Priority::LOW);
+        assert!(normal_rx.try_recv().is_err());
+        assert!(low_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn try_schedule_returns_the_fsm_when_the_tier_queue_is_full() {
+        let (scheduler, normal_rx, _low_rx) = test_scheduler(Some(1));
+
+        assert!(scheduler.try_schedule(Box::new(TestFsm(Priority::NORMAL))).is_ok());
+        // The tier's queue is now at its 1-entry high-watermark: the
+        // self-feeding reschedule path must get the Fsm back instead of
+        // blocking.
+        match scheduler.try_schedule(Box::new(TestFsm(Priority::NORMAL))) {
+            Err(fsm) => assert_eq!(fsm.0, Priority::NORMAL),
+            Ok(()) => panic!("expected the full queue to reject the schedule"),
+        }
+
+        assert_eq!(priority_of(&normal_rx.try_recv().unwrap()), Priority::NORMAL);
+    }
+}
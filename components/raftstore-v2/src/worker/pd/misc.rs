@@ -1,7 +1,10 @@
 // Copyright 2022 TiKV Project Authors. Licensed under Apache-2.0.
 
 use std::{
-    sync::{atomic::Ordering, Arc},
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    future::Future,
+    sync::{atomic::Ordering, Arc, Mutex},
     time::{Duration, Instant},
 };
 
@@ -10,36 +13,276 @@ use engine_traits::{KvEngine, RaftEngine};
 use futures::{compat::Future01CompatExt, FutureExt};
 use pd_client::PdClient;
 use raftstore::{store::TxnExt, Result};
+use rand::Rng;
 use slog::{info, warn};
 use tikv_util::{box_err, timer::GLOBAL_TIMER_HANDLE};
 
 use super::Runner;
 
+/// How long a batch waits after its first region registers before the
+/// coalesced TSO fetch fires, so regions arriving within the same short
+/// window all join one round-trip instead of each firing its own.
+const MAX_TS_COALESCE_WINDOW: Duration = Duration::from_millis(10);
+
+/// One region waiting on the next coalesced max-ts sync.
+struct PendingMaxTsSync {
+    initial_status: u64,
+    txn_ext: Arc<TxnExt>,
+}
+
+/// Batches pending [`Runner::handle_update_max_timestamp`] calls so a single
+/// TSO fetch (or `async_flush`) can resolve all of them at once, instead of
+/// every region independently round-tripping to PD on, e.g., a mass leader
+/// transfer.
+///
+/// Regions [`register`](MaxTsCoalescer::register) into a shared pending map.
+/// The first region to register into an otherwise-empty batch is told to
+/// schedule the tick that will eventually drain it; every region that
+/// registers afterwards just joins that same batch. Regions that arrive
+/// after the tick has already drained the map become the first registrant of
+/// the *next* batch instead.
+///
+/// Constructed once in `Runner::new` and stored as `Runner::max_ts_coalescer`.
+#[derive(Clone, Default)]
+pub struct MaxTsCoalescer {
+    pending: Arc<Mutex<HashMap<u64, PendingMaxTsSync>>>,
+}
+
+impl MaxTsCoalescer {
+    pub fn new() -> Self {
+        MaxTsCoalescer::default()
+    }
+
+    /// Registers `region_id` for the next coalesced sync, overwriting any
+    /// still-pending registration for the same region with the latest
+    /// `initial_status`. Returns `true` when the caller must schedule the
+    /// tick that will drain this batch, i.e. this was the first region to
+    /// join an otherwise-empty batch.
+    fn register(&self, region_id: u64, initial_status: u64, txn_ext: Arc<TxnExt>) -> bool {
+        let mut pending = self.pending.lock().unwrap();
+        let should_schedule_tick = pending.is_empty();
+        pending.insert(
+            region_id,
+            PendingMaxTsSync {
+                initial_status,
+                txn_ext,
+            },
+        );
+        should_schedule_tick
+    }
+
+    /// Takes every region pending at the moment of the call, leaving
+    /// whatever registers afterwards for the next batch.
+    fn drain(&self) -> HashMap<u64, PendingMaxTsSync> {
+        std::mem::take(&mut *self.pending.lock().unwrap())
+    }
+}
+
+/// Base and cap for the exponential backoff `handle_update_max_timestamp`
+/// applies between failed PD round-trips, so a briefly-unreachable PD isn't
+/// busy-retried by every region at once.
+const UPDATE_MAX_TS_BACKOFF_BASE: Duration = Duration::from_millis(10);
+const UPDATE_MAX_TS_BACKOFF_CAP: Duration = Duration::from_secs(3);
+
+/// Computes `min(base * 2^attempt, cap)`, then returns a uniformly random
+/// duration in `[0, that]` (full jitter), so many regions backing off at the
+/// same time don't all retry in lockstep.
+fn backoff_with_full_jitter(attempt: u32) -> Duration {
+    let capped = UPDATE_MAX_TS_BACKOFF_BASE
+        .saturating_mul(1u32 << attempt.min(31))
+        .min(UPDATE_MAX_TS_BACKOFF_CAP);
+    let jittered_millis = rand::thread_rng().gen_range(0..=capped.as_millis() as u64);
+    Duration::from_millis(jittered_millis)
+}
+
+/// One region's progress as tracked by [`ResolvedTsTracker`].
+struct ResolvedTsEntry {
+    resolved_ts: u64,
+    penalty: u32,
+    event_time: Instant,
+    version: u64,
+}
+
+struct ResolvedTsTrackerInner {
+    regions: HashMap<u64, ResolvedTsEntry>,
+    /// Min-heap of `(resolved_ts, region_id, version)`, giving the store-wide
+    /// minimum resolved ts in O(log n). Entries become stale whenever the
+    /// region they describe is upserted again or removed; `store_min` lazily
+    /// discards those as it encounters them rather than eagerly cleaning the
+    /// heap up front.
+    heap: BinaryHeap<Reverse<(u64, u64, u64)>>,
+    next_version: u64,
+}
+
+/// Tracks each region's resolved ts so the store-wide minimum -- what gets
+/// reported to PD -- is always available without rescanning every region,
+/// and so a region whose resolved ts is stuck can be detected and surfaced
+/// instead of silently pinning the store-wide minimum forever.
+///
+/// A region that hasn't advanced in `stall_threshold` gets its `penalty`
+/// bumped instead of immediately alarming, absorbing transient stalls (e.g.
+/// a brief network blip); only once `penalty` reaches `penalty_limit` does
+/// [`advance_tick`](ResolvedTsTracker::advance_tick) report it as stuck.
+///
+/// [`remove`](ResolvedTsTracker::remove) must be called when a region is
+/// merged away or otherwise removed, so a dead region's last-known resolved
+/// ts can never keep pinning the store-wide minimum -- the failure mode that
+/// can otherwise stall CDC clients across a region merge.
+pub struct ResolvedTsTracker {
+    inner: Mutex<ResolvedTsTrackerInner>,
+    penalty_limit: u32,
+    stall_threshold: Duration,
+}
+
+impl ResolvedTsTracker {
+    pub fn new(penalty_limit: u32, stall_threshold: Duration) -> Self {
+        ResolvedTsTracker {
+            inner: Mutex::new(ResolvedTsTrackerInner {
+                regions: HashMap::new(),
+                heap: BinaryHeap::new(),
+                next_version: 0,
+            }),
+            penalty_limit,
+            stall_threshold,
+        }
+    }
+
+    /// Upserts `region_id`'s resolved ts. Callers report progress for
+    /// individual regions through this instead of pre-computing the
+    /// store-wide minimum themselves.
+    ///
+    /// Only resets `penalty`/`event_time` when `resolved_ts` actually
+    /// advances past the previously stored value. Reports arrive on a
+    /// periodic tick, so a region that's genuinely stuck keeps re-reporting
+    /// the *same* resolved_ts every tick; resetting the stall clock
+    /// unconditionally would refresh it forever and the stuck region would
+    /// never be detected.
+    pub fn upsert(&self, region_id: u64, resolved_ts: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        let version = inner.next_version;
+        inner.next_version += 1;
+        let (penalty, event_time) = match inner.regions.get(&region_id) {
+            Some(entry) if entry.resolved_ts >= resolved_ts => (entry.penalty, entry.event_time),
+            _ => (0, Instant::now()),
+        };
+        inner.regions.insert(
+            region_id,
+            ResolvedTsEntry {
+                resolved_ts,
+                penalty,
+                event_time,
+                version,
+            },
+        );
+        inner.heap.push(Reverse((resolved_ts, region_id, version)));
+    }
+
+    /// Purges `region_id`, e.g. after it's merged into another region or
+    /// otherwise removed.
+    pub fn remove(&self, region_id: u64) {
+        self.inner.lock().unwrap().regions.remove(&region_id);
+    }
+
+    /// Returns the store-wide minimum resolved ts, discarding any heap
+    /// entries superseded by a later `upsert` or `remove` as it goes.
+    pub fn store_min(&self) -> Option<u64> {
+        let mut inner = self.inner.lock().unwrap();
+        loop {
+            let &Reverse((resolved_ts, region_id, version)) = inner.heap.peek()?;
+            match inner.regions.get(&region_id) {
+                Some(entry) if entry.version == version => return Some(resolved_ts),
+                _ => {
+                    inner.heap.pop();
+                }
+            }
+        }
+    }
+
+    /// Scans every tracked region for ones whose resolved ts hasn't moved in
+    /// `stall_threshold`. Regions still under `penalty_limit` get their
+    /// penalty bumped and are re-inserted into the heap (so a
+    /// now-superseded copy of their entry gets discarded by `store_min`
+    /// instead of being returned) without being reported as stuck; regions
+    /// that have exhausted their penalty budget are returned so the caller
+    /// can warn and/or trigger lock resolution for them.
+    pub fn advance_tick(&self) -> Vec<u64> {
+        let mut inner = self.inner.lock().unwrap();
+        let stalled: Vec<u64> = inner
+            .regions
+            .iter()
+            .filter(|(_, entry)| entry.event_time.elapsed() > self.stall_threshold)
+            .map(|(region_id, _)| *region_id)
+            .collect();
+
+        let mut stuck = Vec::new();
+        for region_id in stalled {
+            let version = inner.next_version;
+            inner.next_version += 1;
+            let entry = inner.regions.get_mut(&region_id).unwrap();
+            if entry.penalty < self.penalty_limit {
+                entry.penalty += 1;
+                entry.event_time = Instant::now();
+                entry.version = version;
+                let resolved_ts = entry.resolved_ts;
+                inner.heap.push(Reverse((resolved_ts, region_id, version)));
+            } else {
+                stuck.push(region_id);
+            }
+        }
+        stuck
+    }
+}
+
 impl<EK, ER, T> Runner<EK, ER, T>
 where
     EK: KvEngine,
     ER: RaftEngine,
     T: PdClient + 'static,
 {
+    /// Registers `region_id` into the store's [`MaxTsCoalescer`] and, if it's
+    /// the first region to join an otherwise-empty batch, spawns the task
+    /// that will fetch one TSO (or `async_flush`) for the whole batch and
+    /// resolve every region registered by the time it fires. This turns what
+    /// used to be one PD round-trip per region (e.g. on a mass leader
+    /// transfer) into roughly one per `MAX_TS_COALESCE_WINDOW`.
     pub fn handle_update_max_timestamp(
         &mut self,
         region_id: u64,
         initial_status: u64,
         txn_ext: Arc<TxnExt>,
     ) {
+        if !self
+            .max_ts_coalescer
+            .register(region_id, initial_status, txn_ext)
+        {
+            // Joined an in-flight batch; whichever region scheduled its tick
+            // will resolve us too.
+            return;
+        }
+
         let pd_client = self.pd_client.clone();
         let concurrency_manager = self.concurrency_manager.clone();
         let causal_ts_provider = self.causal_ts_provider.clone();
         let logger = self.logger.clone();
         let shutdown = self.shutdown.clone();
-        let log_interval = Duration::from_secs(5);
-        let mut last_log_ts = Instant::now().checked_sub(log_interval).unwrap();
+        let coalescer = self.max_ts_coalescer.clone();
 
         let f = async move {
-            let mut success = false;
-            while txn_ext.max_ts_sync_status.load(Ordering::SeqCst) == initial_status
-                && !shutdown.load(Ordering::Relaxed)
-            {
+            // Give other regions a short window to join this batch before
+            // spending a PD round-trip on it.
+            let coalesce_deadline = Instant::now() + MAX_TS_COALESCE_WINDOW;
+            GLOBAL_TIMER_HANDLE.delay(coalesce_deadline).compat().await.ok();
+            // Close this batch before fetching: a region that registers while
+            // the fetch is in flight finds the map empty and starts the next
+            // batch, so every region resolved below registered before its TSO
+            // was requested.
+            let batch = coalescer.drain();
+
+            let log_interval = Duration::from_secs(5);
+            let mut last_log_ts = Instant::now().checked_sub(log_interval).unwrap();
+            let mut attempt = 0u32;
+            let mut ts_applied = false;
+            while !shutdown.load(Ordering::Relaxed) {
                 // On leader transfer / region merge, RawKV API v2 need to
                 // invoke causal_ts_provider.flush() to renew
                 // cached TSO, to ensure that the next TSO
@@ -66,41 +309,62 @@ where
 
                 match res {
                     Ok(()) => {
-                        success = txn_ext
-                            .max_ts_sync_status
-                            .compare_exchange(
-                                initial_status,
-                                initial_status | 1,
-                                Ordering::SeqCst,
-                                Ordering::SeqCst,
-                            )
-                            .is_ok();
+                        ts_applied = true;
                         break;
                     }
                     Err(e) => {
                         if last_log_ts.elapsed() > log_interval {
                             warn!(
                                 logger,
-                                "failed to update max timestamp for region";
-                                "region_id" => region_id,
+                                "failed to fetch coalesced max timestamp";
                                 "error" => ?e
                             );
                             last_log_ts = Instant::now();
                         }
+                        let backoff = backoff_with_full_jitter(attempt);
+                        attempt = attempt.saturating_add(1);
+                        let deadline = Instant::now() + backoff;
+                        GLOBAL_TIMER_HANDLE.delay(deadline).compat().await.ok();
                     }
                 }
             }
 
-            if success {
-                info!(logger, "succeed to update max timestamp"; "region_id" => region_id);
-            } else {
-                info!(
-                    logger,
-                    "updating max timestamp is stale";
-                    "region_id" => region_id,
-                    "initial_status" => initial_status,
-                );
+            // Resolve every region that joined this batch. A region's status
+            // only flips if the fetch succeeded *and* nothing else already
+            // advanced it past the status it registered with, preserving the
+            // same per-region invariant the unbatched version enforced.
+            let batch_size = batch.len();
+            let mut succeeded = 0u32;
+            for (region_id, sync) in batch {
+                let success = ts_applied
+                    && sync
+                        .txn_ext
+                        .max_ts_sync_status
+                        .compare_exchange(
+                            sync.initial_status,
+                            sync.initial_status | 1,
+                            Ordering::SeqCst,
+                            Ordering::SeqCst,
+                        )
+                        .is_ok();
+                if success {
+                    succeeded += 1;
+                    info!(logger, "succeed to update max timestamp"; "region_id" => region_id);
+                } else {
+                    info!(
+                        logger,
+                        "updating max timestamp is stale";
+                        "region_id" => region_id,
+                        "initial_status" => sync.initial_status,
+                    );
+                }
             }
+            info!(
+                logger,
+                "resolved a coalesced max timestamp batch";
+                "batch_size" => batch_size,
+                "succeeded" => succeeded,
+            );
         };
 
         let delay = (|| {
@@ -130,4 +394,164 @@ where
         };
         self.remote.spawn(f);
     }
+
+    /// Upserts `region_id`'s resolved ts into `self.resolved_ts_tracker`.
+    /// Replaces pushing a pre-computed store minimum: the tracker derives
+    /// the store-wide minimum itself from whatever regions have reported in.
+    pub fn handle_upsert_resolved_ts(&mut self, region_id: u64, resolved_ts: u64) {
+        self.resolved_ts_tracker.upsert(region_id, resolved_ts);
+    }
+
+    /// Purges `region_id` from `self.resolved_ts_tracker`, e.g. after it's
+    /// merged into another region or otherwise removed, so it can never pin
+    /// the store-wide minimum after it stops reporting.
+    pub fn handle_remove_resolved_ts(&mut self, region_id: u64) {
+        self.resolved_ts_tracker.remove(region_id);
+    }
+
+    /// Periodic tick: reports the tracker's current store-wide minimum
+    /// resolved ts to PD, and warns about any region whose resolved ts has
+    /// been stuck long enough to exhaust its stall penalty.
+    ///
+    /// Does not yet trigger lock resolution itself -- raftstore-v2 has no
+    /// lock resolver wired up for this worker to call into. Left as a
+    /// warning until that's in place.
+    pub fn handle_advance_resolved_ts(&mut self, store_id: u64) {
+        for region_id in self.resolved_ts_tracker.advance_tick() {
+            warn!(
+                self.logger,
+                "region's resolved ts has been stuck";
+                "region_id" => region_id
+            );
+            // TODO: hook into the lock resolver once it's wired up for
+            // raftstore-v2, e.g. `self.lock_resolver.resolve(region_id)`.
+        }
+
+        if let Some(min_resolved_ts) = self.resolved_ts_tracker.store_min() {
+            self.handle_report_min_resolved_ts(store_id, min_resolved_ts);
+        }
+    }
+
+    /// Advances the local concurrency manager's max ts for a follower or
+    /// learner serving a replica/stale read, not just on leader transfer /
+    /// region merge like [`handle_update_max_timestamp`] does.
+    ///
+    /// A replica read must observe a max ts at least as large as the commit
+    /// ts of any write it might be racing, or it can return a result below a
+    /// concurrently-committing async-commit transaction. The caller should
+    /// await the returned future before responding to the read, so the
+    /// response is guaranteed to reflect a max ts that bounds all in-flight
+    /// commits as of `required_ts`.
+    ///
+    /// [`handle_update_max_timestamp`]: Self::handle_update_max_timestamp
+    pub fn handle_advance_max_ts_for_read(
+        &mut self,
+        region_id: u64,
+        required_ts: u64,
+    ) -> impl Future<Output = Result<()>> + Send {
+        let pd_client = self.pd_client.clone();
+        let concurrency_manager = self.concurrency_manager.clone();
+        let causal_ts_provider = self.causal_ts_provider.clone();
+        let logger = self.logger.clone();
+
+        async move {
+            let res: Result<()> = if let Some(causal_ts_provider) = &causal_ts_provider {
+                causal_ts_provider
+                    .async_flush()
+                    .await
+                    .map_err(|e| box_err!(e))
+            } else {
+                pd_client.get_tso().await.map_err(Into::into)
+            }
+            .and_then(|ts| {
+                concurrency_manager
+                    .update_max_ts(ts, "raftstore-v2-replica-read")
+                    .map_err(|e| crate::Error::Other(box_err!(e)))
+            });
+
+            if let Err(e) = &res {
+                warn!(
+                    logger,
+                    "failed to advance max ts for replica read";
+                    "region_id" => region_id,
+                    "required_ts" => required_ts,
+                    "error" => ?e
+                );
+            }
+            res
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn backoff_with_full_jitter_stays_within_the_capped_range() {
+        // attempt 0: base * 2^0 = base, uncapped.
+        assert!(backoff_with_full_jitter(0) <= UPDATE_MAX_TS_BACKOFF_BASE);
+
+        // A large attempt saturates at the cap rather than overflowing.
+        for _ in 0..20 {
+            assert!(backoff_with_full_jitter(31) <= UPDATE_MAX_TS_BACKOFF_CAP);
+        }
+    }
+
+    #[test]
+    fn upsert_only_resets_penalty_and_event_time_on_real_advance() {
+        // Bump the penalty via a tick against a threshold that's already
+        // elapsed, simulating a region that hasn't advanced.
+        let stalled = ResolvedTsTracker::new(3, Duration::from_millis(1));
+        stalled.upsert(1, 10);
+        thread::sleep(Duration::from_millis(5));
+        assert!(stalled.advance_tick().is_empty());
+        let penalty_after_first_tick = stalled.inner.lock().unwrap().regions[&1].penalty;
+        assert_eq!(penalty_after_first_tick, 1);
+
+        // Re-reporting the same resolved_ts must not reset the penalty that
+        // was just accrued.
+        stalled.upsert(1, 10);
+        assert_eq!(stalled.inner.lock().unwrap().regions[&1].penalty, 1);
+
+        // Advancing past the stored value does reset it.
+        stalled.upsert(1, 11);
+        assert_eq!(stalled.inner.lock().unwrap().regions[&1].penalty, 0);
+    }
+
+    #[test]
+    fn advance_tick_reports_stuck_once_penalty_limit_is_exhausted() {
+        let tracker = ResolvedTsTracker::new(2, Duration::from_millis(1));
+        tracker.upsert(1, 10);
+
+        thread::sleep(Duration::from_millis(5));
+        assert_eq!(tracker.advance_tick(), Vec::<u64>::new());
+        thread::sleep(Duration::from_millis(5));
+        assert_eq!(tracker.advance_tick(), Vec::<u64>::new());
+        thread::sleep(Duration::from_millis(5));
+        assert_eq!(tracker.advance_tick(), vec![1]);
+    }
+
+    #[test]
+    fn remove_stops_a_region_from_pinning_the_store_wide_minimum() {
+        let tracker = ResolvedTsTracker::new(3, Duration::from_secs(3600));
+        tracker.upsert(1, 5);
+        tracker.upsert(2, 20);
+        assert_eq!(tracker.store_min(), Some(5));
+
+        tracker.remove(1);
+        assert_eq!(tracker.store_min(), Some(20));
+    }
+
+    #[test]
+    fn store_min_skips_heap_entries_superseded_by_a_later_upsert() {
+        let tracker = ResolvedTsTracker::new(3, Duration::from_secs(3600));
+        tracker.upsert(1, 5);
+        tracker.upsert(1, 15);
+        // The stale (5, 1, _) heap entry must be skipped in favor of the
+        // region's current resolved_ts.
+        assert_eq!(tracker.store_min(), Some(15));
+    }
 }